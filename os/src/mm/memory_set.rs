@@ -10,6 +10,7 @@ use crate::config::{
 use crate::sync::UPSafeCell;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::arch::asm;
 use lazy_static::*;
@@ -33,6 +34,111 @@ lazy_static! {
     pub static ref KERNEL_SPACE: Arc<UPSafeCell<MemorySet>> =
         Arc::new(unsafe { UPSafeCell::new(MemorySet::new_kernel()) });
 }
+/// Number of page-sized slots in the in-kernel backing store.
+const SWAP_SLOTS: usize = 16;
+/// Sentinel slot recorded for a clean page that can be reloaded from its ELF
+/// stash instead of being written back. Fits in the 44-bit ppn field.
+const CLEAN_SLOT: usize = (1 << 44) - 1;
+
+/// Backing store for swapped-out pages.
+///
+/// Owns a fixed set of page-sized slots and the CLOCK hand used to pick a
+/// victim. In a real kernel these slots would live on a block device; here a
+/// fixed in-kernel buffer keeps the mechanism self-contained.
+struct SwapManager {
+    /// page-sized backing slots
+    buffer: Vec<[u8; PAGE_SIZE]>,
+    /// whether each slot is in use
+    used: Vec<bool>,
+    /// CLOCK hand, an index into the resident-page list of a `MemorySet`
+    hand: usize,
+}
+
+impl SwapManager {
+    fn new() -> Self {
+        Self {
+            buffer: vec![[0u8; PAGE_SIZE]; SWAP_SLOTS],
+            used: vec![false; SWAP_SLOTS],
+            hand: 0,
+        }
+    }
+    /// Reserve a free slot, or `None` when the backing store is full.
+    fn alloc_slot(&mut self) -> Option<usize> {
+        let slot = self.used.iter().position(|&u| !u)?;
+        self.used[slot] = true;
+        Some(slot)
+    }
+    /// Release a slot back to the pool.
+    fn free_slot(&mut self, slot: usize) {
+        self.used[slot] = false;
+    }
+    /// Write a page into `slot`.
+    fn write(&mut self, slot: usize, page: &[u8]) {
+        self.buffer[slot].copy_from_slice(page);
+    }
+    /// Read a page back from `slot`.
+    fn read(&self, slot: usize, page: &mut [u8]) {
+        page.copy_from_slice(&self.buffer[slot]);
+    }
+}
+
+lazy_static! {
+    /// Global backing store for page eviction.
+    static ref SWAP_MANAGER: UPSafeCell<SwapManager> =
+        unsafe { UPSafeCell::new(SwapManager::new()) };
+}
+
+/// Largest Sv39 address-space identifier (16-bit ASID field in `satp`).
+const MAX_ASID: usize = 0xFFFF;
+
+/// Allocator of address-space identifiers, recycling freed ids. A recycled
+/// ASID may still have stale translations tagged in the TLB, so its next owner
+/// is flagged for a one-off flush on first activation.
+struct AsidAllocator {
+    current: usize,
+    recycled: Vec<usize>,
+    need_flush: Vec<usize>,
+}
+
+impl AsidAllocator {
+    fn new() -> Self {
+        Self {
+            current: 0,
+            recycled: Vec::new(),
+            need_flush: Vec::new(),
+        }
+    }
+    fn alloc(&mut self) -> usize {
+        if let Some(asid) = self.recycled.pop() {
+            // reused id: its old tagged entries must be purged on first use
+            self.need_flush.push(asid);
+            asid
+        } else {
+            assert!(self.current <= MAX_ASID, "ran out of ASIDs");
+            self.current += 1;
+            self.current - 1
+        }
+    }
+    fn dealloc(&mut self, asid: usize) {
+        self.recycled.push(asid);
+    }
+    /// Consume and report whether `asid` still needs a reuse flush.
+    fn take_flush(&mut self, asid: usize) -> bool {
+        if let Some(pos) = self.need_flush.iter().position(|&a| a == asid) {
+            self.need_flush.swap_remove(pos);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+lazy_static! {
+    /// Global ASID allocator.
+    static ref ASID_ALLOCATOR: UPSafeCell<AsidAllocator> =
+        unsafe { UPSafeCell::new(AsidAllocator::new()) };
+}
+
 /// address space
 /// 地址空间是一系列有关联的逻辑段，这种关联一般是指这些逻辑段属于一个运行的程序
 pub struct MemorySet {
@@ -42,6 +148,8 @@ pub struct MemorySet {
     // 逻辑段 MapArea 的向量 areas
     // 每个 MapArea下则挂着对应[逻辑段中的数据所在的物理页帧]，即地址里用户程序相关数据
     areas: Vec<MapArea>,
+    // 该地址空间的 ASID，会被折入 satp，使 TLB 表项按地址空间打标签
+    asid: usize,
 }
 
 impl MemorySet {
@@ -51,6 +159,7 @@ impl MemorySet {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            asid: ASID_ALLOCATOR.exclusive_access().alloc(),
         }
     }
     /// Get the page table token
@@ -87,6 +196,17 @@ impl MemorySet {
         // RAII生命周期管理相关
         self.areas.push(map_area);
     }
+    /// 以惰性（按需）方式插入一个逻辑段：仅登记 `vpn_range` 与权限，并暂存
+    /// 初始化数据 `data`，但不立即建立任何有效 PTE。物理页帧会在首次访问触发
+    /// 缺页时由 [`MemorySet::handle_page_fault`] 分配。`from_elf` uses this for
+    /// every ELF `Load` segment and the user stack, which are the bulk of a
+    /// freshly-exec'd process's memory and the reason demand paging matters;
+    /// the trap-context page stays eager since the kernel writes it directly
+    /// before the process's first entry to user mode, with no fault to catch it.
+    fn push_lazy(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
+        map_area.data = data.map(|d| d.to_vec());
+        self.areas.push(map_area);
+    }
     /// Mention that trampoline is not collected by areas.
     fn map_trampoline(&mut self) {
         self.page_table.map(
@@ -157,13 +277,16 @@ impl MemorySet {
         );
 
         // 各个kernel stack？
+        // 物理内存恒等映射范围很大，允许折叠成 1 GiB / 2 MiB 大页，
+        // 未对齐的边缘部分会自动回退到 4 KiB 页。
         info!("mapping physical memory");
         memory_set.push(
-            MapArea::new(
+            MapArea::new_with_level(
                 (ekernel as usize).into(),
                 MEMORY_END.into(),
                 MapType::Identical,
                 MapPermission::R | MapPermission::W,
+                MapLevel::Giga,
             ),
             None,
         );
@@ -210,8 +333,8 @@ impl MemorySet {
                 // 通过获得的信息，创建逻辑段
                 let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
                 max_end_vpn = map_area.vpn_range.get_end();
-                //  push 到应用地址空间
-                memory_set.push(
+                // push 到应用地址空间：按需分配，实际内容在首次缺页时才拷贝
+                memory_set.push_lazy(
                     map_area,
                     Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
                 );
@@ -225,7 +348,8 @@ impl MemorySet {
         // 防止保护页面
         user_stack_bottom += PAGE_SIZE;
         let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
-        memory_set.push(
+        // 用户栈同样按需分配：多数应用只会用到栈顶附近的少量页面
+        memory_set.push_lazy(
             MapArea::new(
                 user_stack_bottom.into(),
                 user_stack_top.into(),
@@ -264,12 +388,307 @@ impl MemorySet {
             elf.header.pt2.entry_point() as usize,
         )
     }
-    /// Change page table by writing satp CSR Register.
+    /// Clone an address space for `fork`, sharing every resident `Framed` frame
+    /// except the trap-context page copy-on-write instead of deep-copying it.
+    /// Pages not yet faulted in stay unmapped in the child, which keeps each
+    /// area's initialization stash to fault them in. Each shared page is re-mapped
+    /// into the child with the same `PhysPageNum`, the `W` bit stripped from
+    /// both parent and child, and the software `COW` bit set; the backing
+    /// `Arc<FrameTracker>` is cloned so the frame lives until the last sharer
+    /// drops it. A later store fault on such a page is resolved by
+    /// [`MemorySet::cow_fault`].
+    pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
+        let mut memory_set = Self::new_bare();
+        memory_set.map_trampoline();
+        let trap_cx_vpn: VirtPageNum = VirtAddr::from(TRAP_CONTEXT_BASE).floor();
+        for area in user_space.areas.iter() {
+            let mut new_area = MapArea {
+                vpn_range: VPNRange::new(area.vpn_range.get_start(), area.vpn_range.get_end()),
+                data_frames: BTreeMap::new(),
+                map_type: area.map_type,
+                map_perm: area.map_perm,
+                page_size: area.page_size,
+                data: area.data.clone(),
+            };
+            for vpn in area.vpn_range {
+                let src_pte = match user_space.page_table.translate(vpn) {
+                    Some(pte) if pte.is_valid() => pte,
+                    // not faulted in yet: the child faults it in from its own stash
+                    _ => continue,
+                };
+                let ppn = src_pte.ppn();
+                match area.map_type {
+                    MapType::Framed if vpn == trap_cx_vpn => {
+                        // the kernel writes the TrapContext through its physical page
+                        // without faulting, so the child gets a private copy up front
+                        let frame = frame_alloc().unwrap();
+                        frame.ppn.get_bytes_array().copy_from_slice(ppn.get_bytes_array());
+                        memory_set.page_table.map(vpn, frame.ppn, src_pte.flags());
+                        new_area.data_frames.insert(vpn, Arc::new(frame));
+                    }
+                    MapType::Framed => {
+                        // share the parent's frame copy-on-write
+                        let frame = area.data_frames.get(&vpn).unwrap().clone();
+                        new_area.data_frames.insert(vpn, frame);
+                        let cow_flags = (src_pte.flags() & !PTEFlags::W) | PTEFlags::COW;
+                        memory_set.page_table.map(vpn, ppn, cow_flags);
+                        // demote the parent entry to the same read-only COW state;
+                        // it was writable until now, so the parent's (still live)
+                        // TLB entry for it must be purged
+                        user_space.page_table.make_cow(vpn);
+                        user_space.flush_vpn(vpn);
+                    }
+                    MapType::Identical => {
+                        memory_set.page_table.map(vpn, ppn, src_pte.flags());
+                    }
+                }
+            }
+            memory_set.areas.push(new_area);
+        }
+        memory_set
+    }
+
+    /// `scause` exception code for a store/AMO page fault (RISC-V privileged
+    /// spec). A copy-on-write page is only ever resolved on this cause.
+    const STORE_PAGE_FAULT: usize = 15;
+
+    /// 处理一次缺页异常：先检查被换出的页是否需要换入，再检查是否为写时复制
+    /// 页的写故障，否则在找到包含 `vpn` 的逻辑段后为其分配物理页帧（经由
+    /// [`MemorySet::alloc_frame_or_swap`]，使满载的帧分配器先淘汰一个驻留页），
+    /// 拷贝对应的初始化数据（若有）后插入 PTE，使触发缺页的指令得以重试。若没
+    /// 有任何逻辑段覆盖该地址则返回 `Err(())`，调用者应以此终止触发异常的任务。
+    pub fn handle_page_fault(&mut self, vpn: VirtPageNum, cause: usize) -> Result<(), ()> {
+        // a swapped-out page is paged back in before anything else
+        if self
+            .page_table
+            .translate(vpn)
+            .map_or(false, |pte| pte.is_swapped())
+        {
+            return if self.swap_in(vpn) { Ok(()) } else { Err(()) };
+        }
+        // a store fault on an already-valid page can only mean it is a
+        // copy-on-write page being written for the first time
+        if cause == Self::STORE_PAGE_FAULT && self.cow_fault(vpn) {
+            return Ok(());
+        }
+        if !self
+            .areas
+            .iter()
+            .any(|area| vpn >= area.vpn_range.get_start() && vpn < area.vpn_range.get_end())
+        {
+            return Err(());
+        }
+        // 已经映射过则无需再次处理（例如另一路径先行建立了映射）
+        if self.page_table.translate(vpn).map_or(false, |pte| pte.is_valid()) {
+            return Ok(());
+        }
+        // same accounting as MapArea::map_one for a Framed page, but routed
+        // through alloc_frame_or_swap so a full frame allocator evicts a
+        // resident page from this address space instead of failing outright
+        let frame = self.alloc_frame_or_swap().ok_or(())?;
+        let ppn = frame.ppn;
+        let area = self
+            .areas
+            .iter_mut()
+            .find(|area| vpn >= area.vpn_range.get_start() && vpn < area.vpn_range.get_end())
+            .ok_or(())?;
+        area.data_frames.insert(vpn, Arc::new(frame));
+        let pte_flags = PTEFlags::from_bits(area.map_perm.bits as u16).unwrap();
+        self.page_table.map(vpn, ppn, pte_flags);
+        self.flush_vpn(vpn);
+        let area = self
+            .areas
+            .iter()
+            .find(|area| vpn >= area.vpn_range.get_start() && vpn < area.vpn_range.get_end())
+            .unwrap();
+        area.copy_page_data(&mut self.page_table, vpn);
+        Ok(())
+    }
+
+    /// Resolve a store fault on a copy-on-write page: allocate a fresh frame
+    /// (via [`MemorySet::alloc_frame_or_swap`], evicting a resident page from
+    /// this address space first if the allocator is exhausted), copy the 4 KiB
+    /// contents across, drop this address space's share of the old frame
+    /// (decrementing its refcount) and re-map `vpn` writable. Returns `true`
+    /// if `vpn` was a COW page that was handled, `false` otherwise.
+    pub fn cow_fault(&mut self, vpn: VirtPageNum) -> bool {
+        let pte = match self.page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() && pte.is_cow() => pte,
+            _ => return false,
+        };
+        let old_ppn = pte.ppn();
+        if !self
+            .areas
+            .iter()
+            .any(|area| vpn >= area.vpn_range.get_start() && vpn < area.vpn_range.get_end())
+        {
+            return false;
+        }
+        // copy the shared page into a private frame
+        let frame = match self.alloc_frame_or_swap() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        let new_ppn = frame.ppn;
+        new_ppn
+            .get_bytes_array()
+            .copy_from_slice(old_ppn.get_bytes_array());
+        let area = self
+            .areas
+            .iter_mut()
+            .find(|area| vpn >= area.vpn_range.get_start() && vpn < area.vpn_range.get_end())
+            .unwrap();
+        // dropping the previous Arc here decrements the shared refcount
+        area.data_frames.insert(vpn, Arc::new(frame));
+        // re-map writable and clear the COW marker
+        let flags = (pte.flags() & !PTEFlags::COW) | PTEFlags::W;
+        self.page_table.unmap(vpn);
+        self.page_table.map(vpn, new_ppn, flags);
+        self.flush_vpn(vpn);
+        true
+    }
+
+    /// Evict one resident `Framed` page using a CLOCK (second-chance) sweep
+    /// over all areas. The hand advances through resident VPNs: a page whose
+    /// Accessed (A) bit is set gets its bit cleared and is skipped, otherwise it
+    /// is the victim. A clean page (Dirty bit clear) backed by an ELF stash
+    /// skips writeback and is recorded with [`CLEAN_SLOT`]; a dirty page is
+    /// written to a free backing slot. The frame is then freed and its entry
+    /// replaced with a swapped descriptor. Returns `true` if a page was evicted.
+    pub fn swap_out_one(&mut self) -> bool {
+        // resident Framed pages, in a stable order for the CLOCK hand
+        let mut resident: Vec<VirtPageNum> = Vec::new();
+        for area in &self.areas {
+            if area.map_type == MapType::Framed {
+                resident.extend(area.data_frames.keys().copied());
+            }
+        }
+        if resident.is_empty() {
+            return false;
+        }
+        let n = resident.len();
+        let mut swap = SWAP_MANAGER.exclusive_access();
+        let mut hand = swap.hand % n;
+        for _ in 0..2 * n {
+            let vpn = resident[hand];
+            let pte = self.page_table.translate(vpn).unwrap();
+            if pte.flags().contains(PTEFlags::A) {
+                // second chance: clear A and move on
+                self.page_table.clear_accessed(vpn);
+                self.flush_vpn(vpn);
+                hand = (hand + 1) % n;
+                continue;
+            }
+            // found a victim
+            let ppn = pte.ppn();
+            let dirty = pte.flags().contains(PTEFlags::D);
+            let backed = self
+                .areas
+                .iter()
+                .find(|a| vpn >= a.vpn_range.get_start() && vpn < a.vpn_range.get_end())
+                .map_or(false, |a| a.data.is_some());
+            let slot = if !dirty && backed {
+                // clean & ELF-backed: no need to write anything back
+                CLEAN_SLOT
+            } else {
+                match swap.alloc_slot() {
+                    Some(s) => {
+                        swap.write(s, ppn.get_bytes_array());
+                        s
+                    }
+                    None => return false,
+                }
+            };
+            self.page_table.set_swapped(vpn, slot);
+            self.flush_vpn(vpn);
+            // drop this address space's frame (the Arc), reclaiming the page
+            for area in &mut self.areas {
+                if area.data_frames.remove(&vpn).is_some() {
+                    break;
+                }
+            }
+            swap.hand = (hand + 1) % n;
+            return true;
+        }
+        false
+    }
+
+    /// Page a swapped-out `vpn` back in: allocate a frame (via
+    /// [`MemorySet::alloc_frame_or_swap`], evicting a resident page from this
+    /// address space first if the allocator is exhausted), restore its
+    /// contents from the backing slot (or re-zero/reload from the ELF stash
+    /// for a clean page), free the slot, and re-install a valid PTE. Returns
+    /// `true` if `vpn` was swapped and has been restored.
+    pub fn swap_in(&mut self, vpn: VirtPageNum) -> bool {
+        let pte = match self.page_table.translate(vpn) {
+            Some(pte) if pte.is_swapped() => pte,
+            _ => return false,
+        };
+        let slot = pte.swap_slot();
+        let frame = match self.alloc_frame_or_swap() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        let ppn = frame.ppn;
+        if slot != CLEAN_SLOT {
+            SWAP_MANAGER
+                .exclusive_access()
+                .read(slot, ppn.get_bytes_array());
+            SWAP_MANAGER.exclusive_access().free_slot(slot);
+        }
+        let area = self
+            .areas
+            .iter_mut()
+            .find(|a| vpn >= a.vpn_range.get_start() && vpn < a.vpn_range.get_end())
+            .unwrap();
+        area.data_frames.insert(vpn, Arc::new(frame));
+        let flags = PTEFlags::from_bits(area.map_perm.bits as u16).unwrap();
+        self.page_table.map(vpn, ppn, flags);
+        if slot == CLEAN_SLOT {
+            // clean page: reload its original ELF contents (zero for .bss)
+            area.copy_page_data(&mut self.page_table, vpn);
+        }
+        self.flush_vpn(vpn);
+        true
+    }
+
+    /// Allocate a frame, evicting a resident page from this address space first
+    /// when the global frame allocator is exhausted.
+    pub fn alloc_frame_or_swap(&mut self) -> Option<FrameTracker> {
+        if let Some(frame) = frame_alloc() {
+            return Some(frame);
+        }
+        if self.swap_out_one() {
+            frame_alloc()
+        } else {
+            None
+        }
+    }
+
+    /// Change page table by writing the `satp` CSR, folding in this address
+    /// space's ASID. Because TLB entries are ASID-tagged, a plain switch needs
+    /// no flush; we only purge entries when this ASID has just been recycled
+    /// from a previous owner.
     pub fn activate(&self) {
-        let satp = self.page_table.token();
+        let satp = self.page_table.token() | (self.asid << 44);
+        let reused = ASID_ALLOCATOR.exclusive_access().take_flush(self.asid);
         unsafe {
             satp::write(satp);
-            asm!("sfence.vma");
+            if reused {
+                // purge every entry tagged with this (recycled) ASID
+                asm!("sfence.vma x0, {}", in(reg) self.asid);
+            }
+        }
+    }
+    /// Invalidate just the translation for `vpn` in this address space, using
+    /// a single-page, single-ASID `sfence.vma`. Paths that mutate a live page
+    /// table for the running address space (`map_one`/`unmap_one`/`shrink_to`/
+    /// `append_to`, COW and page-fault handlers) can call this instead of
+    /// flushing the whole TLB.
+    pub fn flush_vpn(&self, vpn: VirtPageNum) {
+        let va: VirtAddr = vpn.into();
+        unsafe {
+            asm!("sfence.vma {}, {}", in(reg) va.0, in(reg) self.asid);
         }
     }
     /// Translate a virtual page number to a page table entry
@@ -284,7 +703,12 @@ impl MemorySet {
             .iter_mut()
             .find(|area| area.vpn_range.get_start() == start.floor())
         {
-            area.shrink_to(&mut self.page_table, new_end.ceil());
+            let old_end = area.vpn_range.get_end();
+            let new_end_vpn = new_end.ceil();
+            area.shrink_to(&mut self.page_table, new_end_vpn);
+            for vpn in VPNRange::new(new_end_vpn, old_end) {
+                self.flush_vpn(vpn);
+            }
             true
         } else {
             false
@@ -299,13 +723,161 @@ impl MemorySet {
             .iter_mut()
             .find(|area| area.vpn_range.get_start() == start.floor())
         {
-            area.append_to(&mut self.page_table, new_end.ceil());
+            let old_end = area.vpn_range.get_end();
+            let new_end_vpn = new_end.ceil();
+            area.append_to(&mut self.page_table, new_end_vpn);
+            for vpn in VPNRange::new(old_end, new_end_vpn) {
+                self.flush_vpn(vpn);
+            }
             true
         } else {
             false
         }
     }
+
+    /// Remove the `MapArea` that starts at `start_vpn`, unmapping its pages.
+    /// Used to tear down a process's kernel stack when its pid is recycled.
+    pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
+        if let Some(idx) = self
+            .areas
+            .iter()
+            .position(|area| area.vpn_range.get_start() == start_vpn)
+        {
+            let range = self.areas[idx].vpn_range;
+            self.areas[idx].unmap(&mut self.page_table);
+            self.areas.remove(idx);
+            for vpn in range {
+                self.flush_vpn(vpn);
+            }
+        }
+    }
+
+    /// Map the anonymous range `[start_va, start_va + len)` with `perm`,
+    /// enforcing the invariant that [`insert_framed_area`] only documents:
+    /// reject an unaligned `start_va` or zero `len`, and refuse to map if the
+    /// requested `VPNRange` intersects any existing area.
+    ///
+    /// [`insert_framed_area`]: MemorySet::insert_framed_area
+    pub fn mmap(
+        &mut self,
+        start_va: VirtAddr,
+        len: usize,
+        perm: MapPermission,
+    ) -> Result<(), MmapError> {
+        if start_va.page_offset() != 0 {
+            return Err(MmapError::Unaligned);
+        }
+        if len == 0 {
+            return Err(MmapError::ZeroLength);
+        }
+        let end_va = VirtAddr::from(start_va.0 + len);
+        let range = VPNRange::new(start_va.floor(), end_va.ceil());
+        if self
+            .areas
+            .iter()
+            .any(|area| ranges_intersect(range, area.vpn_range))
+        {
+            return Err(MmapError::Overlap);
+        }
+        self.push(
+            MapArea::new(start_va, end_va, MapType::Framed, perm),
+            None,
+        );
+        Ok(())
+    }
+
+    /// Unmap the range `[start_va, start_va + len)`. Fails only if some page
+    /// in the range is not currently mapped; the range is free to span
+    /// several adjacent areas (e.g. separate `mmap` calls that happened to
+    /// land back to back), each of which is split into up to two surviving
+    /// pieces (before and after the hole), dropping the frames in between.
+    pub fn munmap(&mut self, start_va: VirtAddr, len: usize) -> Result<(), MmapError> {
+        if start_va.page_offset() != 0 {
+            return Err(MmapError::Unaligned);
+        }
+        if len == 0 {
+            return Err(MmapError::ZeroLength);
+        }
+        let start_vpn = start_va.floor();
+        let end_vpn = VirtAddr::from(start_va.0 + len).ceil();
+        let hole = VPNRange::new(start_vpn, end_vpn);
+        let mut idxs: Vec<usize> = self
+            .areas
+            .iter()
+            .enumerate()
+            .filter(|(_, area)| ranges_intersect(hole, area.vpn_range))
+            .map(|(idx, _)| idx)
+            .collect();
+        idxs.sort_by_key(|&idx| self.areas[idx].vpn_range.get_start().0);
+        // the intersecting areas, sorted by start, must butt up against each
+        // other with no gap and together cover the whole hole
+        let mut covered_to = start_vpn;
+        for &idx in &idxs {
+            let area = &self.areas[idx];
+            if area.vpn_range.get_start() > covered_to {
+                return Err(MmapError::NotMapped);
+            }
+            if area.vpn_range.get_end() > covered_to {
+                covered_to = area.vpn_range.get_end();
+            }
+        }
+        if covered_to < end_vpn {
+            return Err(MmapError::NotMapped);
+        }
+        // remove from the back so the earlier indices in `idxs` stay valid
+        idxs.sort_unstable();
+        for &idx in idxs.iter().rev() {
+            let old = self.areas.remove(idx);
+            let old_start = old.vpn_range.get_start();
+            let old_end = old.vpn_range.get_end();
+            let map_type = old.map_type;
+            let map_perm = old.map_perm;
+            let page_size = old.page_size;
+            let mut frames = old.data_frames;
+            // this area's own slice of the hole (the whole area, if the hole swallows it)
+            let piece_start = if start_vpn > old_start { start_vpn } else { old_start };
+            let piece_end = if end_vpn < old_end { end_vpn } else { old_end };
+            // drop the frames in the hole and clear their page table entries
+            for vpn in VPNRange::new(piece_start, piece_end) {
+                if map_type == MapType::Framed {
+                    frames.remove(&vpn);
+                }
+                self.page_table.unmap(vpn);
+                self.flush_vpn(vpn);
+            }
+            // rebuild the surviving left/right pieces, keeping their live mappings
+            let mut rebuild = |from: VirtPageNum, to: VirtPageNum| {
+                if from >= to {
+                    return;
+                }
+                let mut piece = MapArea {
+                    vpn_range: VPNRange::new(from, to),
+                    data_frames: BTreeMap::new(),
+                    map_type,
+                    map_perm,
+                    page_size,
+                    data: None,
+                };
+                for vpn in VPNRange::new(from, to) {
+                    if let Some(frame) = frames.remove(&vpn) {
+                        piece.data_frames.insert(vpn, frame);
+                    }
+                }
+                self.areas.push(piece);
+            };
+            rebuild(old_start, piece_start);
+            rebuild(piece_end, old_end);
+        }
+        Ok(())
+    }
 }
+impl Drop for MemorySet {
+    fn drop(&mut self) {
+        // recycle the ASID so a future address space can reuse it
+        ASID_ALLOCATOR.exclusive_access().dealloc(self.asid);
+    }
+}
+
 /// map area structure, controls a contiguous piece of virtual memory
 /// 逻辑段，一段实际可用的地址连续的虚拟地址空间
 pub struct MapArea {
@@ -316,11 +888,19 @@ pub struct MapArea {
     // 这些物理页帧被用来存放【实际内存数据而不是作为多级页表中的中间节点】
     // TODO: 这里意思是保存物理页号吗，有点没看懂
     // 这也用到了 RAII 的思想，将这些物理页帧的生命周期绑定到它所在的逻辑段 MapArea 下，当逻辑段被回收之后这些之前分配的物理页帧也会自动地同时被回收。
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    // 物理页帧以 `Arc` 共享，使 copy-on-write 的父子地址空间能够共用同一帧，
+    // 直到最后一个持有者被释放时才真正回收。
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
     // MapType 描述该逻辑段内的所有虚拟页面映射到物理页帧的同一种方式
     // 它是一个枚举类型，在内核当前的实现中支持两种方式
     map_type: MapType,
     map_perm: MapPermission,
+    // 该逻辑段允许使用的最大映射粒度。`Page` 为普通 4 KiB 页；`Identical`
+    // 段可用 `Mega`/`Giga` 将自然对齐的区域折叠成 2 MiB / 1 GiB 大页。
+    page_size: MapLevel,
+    // 惰性分配时暂存的初始化数据（通常来自 ELF 的 Load 段），
+    // 在首次缺页时按页拷贝到新分配的物理页帧；匿名区段为 None。
+    data: Option<Vec<u8>>,
 }
 
 impl MapArea {
@@ -339,8 +919,23 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            page_size: MapLevel::Page,
+            data: None,
         }
     }
+    /// Build an area that may use superpages up to `page_size`. Only meaningful
+    /// for `Identical` regions; `Framed` areas are always 4 KiB.
+    pub fn new_with_level(
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_type: MapType,
+        map_perm: MapPermission,
+        page_size: MapLevel,
+    ) -> Self {
+        let mut area = Self::new(start_va, end_va, map_type, map_perm);
+        area.page_size = page_size;
+        area
+    }
     /// 对单个虚拟页面映射/解映射
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         let ppn: PhysPageNum;
@@ -356,12 +951,12 @@ impl MapArea {
                 let frame = frame_alloc().unwrap();
                 // 此时页表项中的物理页号自然就是 这个被分配的物理页帧的物理页号
                 ppn = frame.ppn;
-                // 还需要将这个物理页帧挂在逻辑段的 data_frames 字段下
-                self.data_frames.insert(vpn, frame);
+                // 还需要将这个物理页帧挂在逻辑段的 data_frames 字段下（以 Arc 共享）
+                self.data_frames.insert(vpn, Arc::new(frame));
             }
         }
         // 页表项的标志位来源于当前逻辑段的类型为 MapPermission 的统一配置，只需将其转换为 PTEFlags
-        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits as u16).unwrap();
         // 调用多级页表 PageTable 的 map 接口来插入键值对
         page_table.map(vpn, ppn, pte_flags);
     }
@@ -377,15 +972,38 @@ impl MapArea {
     }
     /// 当前逻辑段到物理内存的映射从传入的该逻辑段所属的地址空间的 多级页表中加入
     pub fn map(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+        // 只有 `Identical` 且允许大页的逻辑段才折叠；其余沿用逐页 4 KiB 映射
+        if self.map_type != MapType::Identical || self.page_size == MapLevel::Page {
+            for vpn in self.vpn_range {
+                self.map_one(page_table, vpn);
+            }
+            return;
+        }
+        let flags = PTEFlags::from_bits(self.map_perm.bits as u16).unwrap();
+        let end = self.vpn_range.get_end().0;
+        let mut v = self.vpn_range.get_start().0;
+        while v < end {
+            let level = chunk_level(v, end, self.page_size);
+            page_table.map_level(VirtPageNum(v), PhysPageNum(v), flags, level.pte_index());
+            v += level.page_count();
         }
     }
     #[allow(unused)]
     /// 当前逻辑段到物理内存的映射从传入的该逻辑段所属的地址空间的 多级页表中删除
     pub fn unmap(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
-            self.unmap_one(page_table, vpn);
+        if self.map_type != MapType::Identical || self.page_size == MapLevel::Page {
+            for vpn in self.vpn_range {
+                self.unmap_one(page_table, vpn);
+            }
+            return;
+        }
+        // 以与 `map` 相同的分块方式逐个清除大页叶子项
+        let end = self.vpn_range.get_end().0;
+        let mut v = self.vpn_range.get_start().0;
+        while v < end {
+            let level = chunk_level(v, end, self.page_size);
+            page_table.unmap(VirtPageNum(v));
+            v += level.page_count();
         }
     }
     #[allow(unused)]
@@ -436,6 +1054,82 @@ impl MapArea {
             current_vpn.step();
         }
     }
+    /// 惰性缺页时，将 `data` 中对应 `vpn` 的那一页初始化数据拷贝到其物理页帧。
+    /// 若该逻辑段没有初始化数据（匿名映射），则保持 `frame_alloc` 清零后的内容。
+    fn copy_page_data(&self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        if let Some(data) = &self.data {
+            let offset = (vpn.0 - self.vpn_range.get_start().0) * PAGE_SIZE;
+            if offset >= data.len() {
+                return;
+            }
+            let src = &data[offset..data.len().min(offset + PAGE_SIZE)];
+            let dst =
+                &mut page_table.translate(vpn).unwrap().ppn().get_bytes_array()[..src.len()];
+            dst.copy_from_slice(src);
+        }
+    }
+}
+
+/// Reasons a checked `mmap`/`munmap` request can be rejected.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MmapError {
+    /// `start_va` is not page-aligned
+    Unaligned,
+    /// the requested length is zero
+    ZeroLength,
+    /// the range intersects an already-mapped area
+    Overlap,
+    /// the range to unmap is not fully covered by existing areas
+    NotMapped,
+}
+
+/// Do two half-open `VPNRange`s intersect?
+fn ranges_intersect(a: VPNRange, b: VPNRange) -> bool {
+    a.get_start() < b.get_end() && b.get_start() < a.get_end()
+}
+
+#[derive(Copy, Clone, PartialEq, PartialOrd, Debug)]
+/// Mapping granularity of a logical segment: a 4 KiB page or an Sv39 superpage.
+pub enum MapLevel {
+    /// 4 KiB page (level-0 leaf)
+    Page,
+    /// 2 MiB superpage (level-1 leaf)
+    Mega,
+    /// 1 GiB superpage (level-2 leaf)
+    Giga,
+}
+
+impl MapLevel {
+    /// Number of 4 KiB pages covered by one entry at this level.
+    fn page_count(self) -> usize {
+        match self {
+            MapLevel::Page => 1,
+            MapLevel::Mega => 512,
+            MapLevel::Giga => 512 * 512,
+        }
+    }
+    /// Depth at which the leaf entry lives (0 = 1 GiB, 1 = 2 MiB, 2 = 4 KiB).
+    fn pte_index(self) -> usize {
+        match self {
+            MapLevel::Giga => 0,
+            MapLevel::Mega => 1,
+            MapLevel::Page => 2,
+        }
+    }
+}
+
+/// Pick the largest level (≤ `max`) whose superpage is naturally aligned at
+/// `start` and fits before `end`, falling back to a 4 KiB page.
+fn chunk_level(start: usize, end: usize, max: MapLevel) -> MapLevel {
+    for level in [MapLevel::Giga, MapLevel::Mega] {
+        if level <= max {
+            let count = level.page_count();
+            if start % count == 0 && start + count <= end {
+                return level;
+            }
+        }
+    }
+    MapLevel::Page
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
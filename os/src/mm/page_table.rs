@@ -1,6 +1,7 @@
 //! Implementation of [`PageTableEntry`] and [`PageTable`].
 
 use super::{frame_alloc, FrameTracker, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
+use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
@@ -8,7 +9,10 @@ use bitflags::*;
 // 页表控制位
 bitflags! {
     /// page table entry flags
-    pub struct PTEFlags: u8 {
+    ///
+    /// Bits 0..=7 are the architectural Sv39 flags; bit 8 uses one of the
+    /// reserved-for-software (RSW) bits to mark a copy-on-write page.
+    pub struct PTEFlags: u16 {
         const V = 1 << 0;
         const R = 1 << 1;
         const W = 1 << 2;
@@ -17,6 +21,10 @@ bitflags! {
         const G = 1 << 5;
         const A = 1 << 6;
         const D = 1 << 7;
+        /// copy-on-write marker (software bit)
+        const COW = 1 << 8;
+        /// page swapped out to backing store (software bit)
+        const SWAPPED = 1 << 9;
     }
 }
 
@@ -46,9 +54,22 @@ impl PageTableEntry {
         (self.bits >> 10 & ((1usize << 44) - 1)).into()
     }
     /// Get the flags from the page table entry
-    /// 
+    ///
     pub fn flags(&self) -> PTEFlags {
-        PTEFlags::from_bits(self.bits as u8).unwrap()
+        // the flag bits live in the low 10 bits of the entry (0..=9)
+        PTEFlags::from_bits((self.bits & ((1 << 10) - 1)) as u16).unwrap()
+    }
+    /// Is this entry a copy-on-write page?
+    pub fn is_cow(&self) -> bool {
+        (self.flags() & PTEFlags::COW) != PTEFlags::empty()
+    }
+    /// Is this page currently swapped out to the backing store?
+    pub fn is_swapped(&self) -> bool {
+        (self.flags() & PTEFlags::SWAPPED) != PTEFlags::empty()
+    }
+    /// Backing-store slot id recorded in a swapped-out entry.
+    pub fn swap_slot(&self) -> usize {
+        self.ppn().0
     }
     /// The page pointered by page table entry is valid?
     /// 通过v位判断是否合法
@@ -143,6 +164,33 @@ impl PageTable {
             if !pte.is_valid() {
                 return None;
             }
+            // 若在非底层遇到叶子项（设置了 R/W/X），说明这是一个大页映射，直接返回
+            if pte.readable() || pte.writable() || pte.executable() {
+                result = Some(pte);
+                break;
+            }
+            ppn = pte.ppn();
+        }
+        result
+    }
+    /// Walk down to `level` (0 = 1 GiB slot, 1 = 2 MiB, 2 = 4 KiB), creating
+    /// intermediate nodes as needed, and return the entry at that level so a
+    /// leaf can be installed higher in the tree for a superpage.
+    fn find_pte_create_level(&mut self, vpn: VirtPageNum, level: usize) -> Option<&mut PageTableEntry> {
+        let idxs = vpn.indexes();
+        let mut ppn = self.root_ppn;
+        let mut result: Option<&mut PageTableEntry> = None;
+        for i in 0..=level {
+            let pte = &mut ppn.get_pte_array()[idxs[i]];
+            if i == level {
+                result = Some(pte);
+                break;
+            }
+            if !pte.is_valid() {
+                let frame = frame_alloc().unwrap();
+                *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+                self.frames.push(frame);
+            }
             ppn = pte.ppn();
         }
         result
@@ -150,6 +198,11 @@ impl PageTable {
     /// 为了执行页表搜索，操作系统维护虚拟页号到页表项的映射
     /// set the map between virtual page number and physical page number
     /// 插入键值对
+    ///
+    /// Does not flush the TLB: a page table is as often built for an address
+    /// space that is not yet (or no longer) active as it is mutated live.
+    /// Callers touching a live, running address space must invalidate `vpn`
+    /// themselves, e.g. via [`MemorySet::flush_vpn`](super::MemorySet::flush_vpn).
     #[allow(unused)]
     pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
         // 根据虚拟页号找到页表项
@@ -158,8 +211,18 @@ impl PageTable {
         // 根据参数修改页表项
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
     }
+    /// Install a leaf entry for `vpn` at `level` (0 = 1 GiB, 1 = 2 MiB,
+    /// 2 = 4 KiB), mapping a naturally-aligned superpage with a single PTE.
+    /// Does not flush the TLB; see [`map`](Self::map).
+    pub fn map_level(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags, level: usize) {
+        let pte = self.find_pte_create_level(vpn, level).unwrap();
+        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+    }
     /// remove the map between virtual page number and physical page number
     /// 删除键值对
+    ///
+    /// Does not flush the TLB; see [`map`](Self::map).
     #[allow(unused)]
     pub fn unmap(&mut self, vpn: VirtPageNum) {
         // 根据虚拟页号找到页表项
@@ -168,6 +231,33 @@ impl PageTable {
         // 清空页表项
         *pte = PageTableEntry::empty();
     }
+    /// Demote `vpn` to copy-on-write: clear the `W` bit and set the software
+    /// `COW` bit, leaving the frame shared until the next store fault. Does
+    /// not flush the TLB; see [`map`](Self::map).
+    pub fn make_cow(&self, vpn: VirtPageNum) {
+        if let Some(pte) = self.find_pte(vpn) {
+            let flags = (pte.flags() & !PTEFlags::W) | PTEFlags::COW;
+            *pte = PageTableEntry::new(pte.ppn(), flags);
+        }
+    }
+    /// Clear the Accessed (A) bit of `vpn`, giving it a "second chance" in the
+    /// CLOCK replacement algorithm. Does not flush the TLB; see [`map`](Self::map).
+    pub fn clear_accessed(&self, vpn: VirtPageNum) {
+        if let Some(pte) = self.find_pte(vpn) {
+            let flags = pte.flags() & !PTEFlags::A;
+            *pte = PageTableEntry::new(pte.ppn(), flags);
+        }
+    }
+    /// Replace `vpn`'s entry with an invalid "swapped" descriptor that records
+    /// the backing-store `slot`, freeing the translation for reuse. Does not
+    /// flush the TLB; see [`map`](Self::map).
+    pub fn set_swapped(&self, vpn: VirtPageNum, slot: usize) {
+        if let Some(pte) = self.find_pte(vpn) {
+            *pte = PageTableEntry {
+                bits: slot << 10 | PTEFlags::SWAPPED.bits as usize,
+            };
+        }
+    }
     /// get the page table entry from the virtual page number
     /// 用户接口?
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
@@ -201,3 +291,26 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
     }
     v
 }
+
+/// Load a null-terminated string from user space through the page table, one
+/// byte at a time until the terminating `\0`.
+pub fn translated_str(token: usize, ptr: *const u8) -> String {
+    let page_table = PageTable::from_token(token);
+    let mut string = String::new();
+    let mut va = ptr as usize;
+    loop {
+        let ch: u8 = *(page_table
+            .translate(VirtAddr::from(va).floor())
+            .unwrap()
+            .ppn()
+            .get_bytes_array())
+            .get(VirtAddr::from(va).page_offset())
+            .unwrap();
+        if ch == 0 {
+            break;
+        }
+        string.push(ch as char);
+        va += 1;
+    }
+    string
+}
@@ -20,6 +20,20 @@ const SYSCALL_YIELD: usize = 124;
 const SYSCALL_GET_TIME: usize = 169;
 /// taskinfo syscall
 const SYSCALL_TASK_INFO: usize = 410;
+/// set priority syscall
+const SYSCALL_SET_PRIORITY: usize = 140;
+/// mmap syscall
+const SYSCALL_MMAP: usize = 222;
+/// munmap syscall
+const SYSCALL_MUNMAP: usize = 215;
+/// getpid syscall
+const SYSCALL_GETPID: usize = 172;
+/// fork syscall
+const SYSCALL_FORK: usize = 220;
+/// exec syscall
+const SYSCALL_EXEC: usize = 221;
+/// waitpid syscall
+const SYSCALL_WAITPID: usize = 260;
 
 mod fs;
 pub(crate) mod process;
@@ -27,25 +41,32 @@ pub(crate) mod process;
 use fs::*;
 use process::*;
 
-use crate::{task::TASK_MANAGER};
-/// handle syscall exception with `syscall_id` and other arguments 
+use crate::{task::TASK_MANAGER, timer::get_time_ms};
+/// handle syscall exception with `syscall_id` and other arguments
 pub fn syscall(syscall_id: usize , args: [usize; 3]) -> isize {
     trace!("syscall: id is {} and args = {:?}",syscall_id,args);
     //let last_time = get_time();
-    match syscall_id {
+    let ret = match syscall_id {
         SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
         SYSCALL_EXIT => sys_exit(args[0] as i32),
         SYSCALL_YIELD => sys_yield(),
         SYSCALL_GET_TIME => sys_get_time(args[0] as *mut TimeVal, args[1]),
         SYSCALL_TASK_INFO => sys_task_info(args[0] as *mut TaskInfo),
+        SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
+        SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2]),
+        SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
+        SYSCALL_GETPID => sys_getpid(),
+        SYSCALL_FORK => sys_fork(),
+        SYSCALL_EXEC => sys_exec(args[0] as *const u8),
+        SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32),
         _ => panic!("Unsupported syscall_id: {}", syscall_id),
     };
     // 从TASK_MANAGER 根据索引获得tcb，task info => TaskManagerInner current task
     // 运行时间 time 返回系统调用时刻距离任务第一次被调度时刻的时长，也就是说这个时长可能包含该任务被其他任务抢占后的等待重新调度的时间。
 
     //TODO 返回ref而不是新建的
-    let current_task = TASK_MANAGER.get_current_task();
-    trace!("Current task status: {:?}", current_task.task_info.status);
-    TASK_MANAGER.update_task_info(syscall_id);
-    return 0;
+    let task_info = TASK_MANAGER.get_current_task_info();
+    trace!("Current task status: {:?}", task_info.status);
+    TASK_MANAGER.update_task_info(syscall_id, get_time_ms());
+    ret
 }
@@ -1,7 +1,13 @@
 //! Process management syscalls
+use core::mem::size_of;
+
 use crate::{
     config::MAX_SYSCALL_NUM,
-    task::{exit_current_and_run_next, suspend_current_and_run_next, TaskStatus},
+    mm::{translated_byte_buffer, translated_str},
+    task::{
+        current_user_token, exec, exit_current_and_run_next, fork, getpid, mmap, munmap,
+        set_current_priority, suspend_current_and_run_next, waitpid, TaskStatus, TASK_MANAGER,
+    },
     timer::get_time_us,
 };
 
@@ -37,7 +43,7 @@ impl Default for TaskInfo {
 /// task exits and submit an exit code
 pub fn sys_exit(exit_code: i32) -> ! {
     trace!("[kernel] Application exited with code {}", exit_code);
-    exit_current_and_run_next();
+    exit_current_and_run_next(exit_code);
     panic!("Unreachable in sys_exit!");
 }
 
@@ -48,30 +54,92 @@ pub fn sys_yield() -> isize {
     0
 }
 
+/// Copy `value` into user space through the page table, handling a struct that
+/// may straddle a page boundary by writing each translated fragment in turn.
+fn copy_to_user<T>(ptr: *const u8, value: &T) {
+    let buffers = translated_byte_buffer(current_user_token(), ptr, size_of::<T>());
+    let mut src = value as *const T as *const u8;
+    for buffer in buffers {
+        // SAFETY: `buffer.len()` bytes starting at `src` stay inside `value`,
+        // since the fragments together cover exactly `size_of::<T>()` bytes.
+        unsafe {
+            buffer.copy_from_slice(core::slice::from_raw_parts(src, buffer.len()));
+            src = src.add(buffer.len());
+        }
+    }
+}
+
 /// get time with second and microsecond
 pub fn sys_get_time(ts: *mut TimeVal, _tz: usize) -> isize {
     trace!("kernel: sys_get_time");
     let us = get_time_us();
-    unsafe {
-        *ts = TimeVal {
-            sec: us / 1_000_000,
-            usec: us % 1_000_000,
-        };
-    }
+    let time_val = TimeVal {
+        sec: us / 1_000_000,
+        usec: us % 1_000_000,
+    };
+    copy_to_user(ts as *const u8, &time_val);
     0
 }
 
 /// YOUR JOB: Finish sys_task_info to pass testcases
-pub fn sys_task_info(_ti: *mut TaskInfo) -> isize {
-    let task_info :TaskInfo = unsafe {*_ti};
+pub fn sys_task_info(ti: *mut TaskInfo) -> isize {
+    trace!("kernel: sys_task_info");
+    // build the answer from the current task's bookkeeping, then write it out
+    let task_info = TASK_MANAGER.get_current_task_info();
     trace!("[sys_task_info] current task's status is {:?}",task_info.status);
     trace!("[sys_task_info] current task's time is {:?}",task_info.time);
     trace!("[sys_task_info] current task's syscall_times is {:?}",task_info.syscall_times);
-    // TODO:error check
-    return 0;
+    copy_to_user(ti as *const u8, &task_info);
+    0
+}
+
+/// set the priority of the current task; `prio` must be at least 2
+pub fn sys_set_priority(prio: isize) -> isize {
+    trace!("kernel: sys_set_priority");
+    if prio < 2 {
+        return -1;
+    }
+    set_current_priority(prio as usize)
+}
+
+/// map `len` bytes of anonymous memory at the page-aligned `start`
+pub fn sys_mmap(start: usize, len: usize, prot: usize) -> isize {
+    trace!("kernel: sys_mmap");
+    mmap(start, len, prot)
+}
+
+/// unmap the range of `len` bytes at the page-aligned `start`
+pub fn sys_munmap(start: usize, len: usize) -> isize {
+    trace!("kernel: sys_munmap");
+    munmap(start, len)
+}
+
+/// get the pid of the current process
+pub fn sys_getpid() -> isize {
+    trace!("kernel: sys_getpid");
+    getpid()
+}
+
+/// fork a child process, returning the child pid in the parent and 0 in the child
+pub fn sys_fork() -> isize {
+    trace!("kernel: sys_fork");
+    fork()
+}
+
+/// replace the current process image with the app named by the user string `path`
+pub fn sys_exec(path: *const u8) -> isize {
+    trace!("kernel: sys_exec");
+    let path = translated_str(current_user_token(), path);
+    exec(path.as_str())
+}
+
+/// wait for a child process to become a zombie, writing its exit code out
+pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+    trace!("kernel: sys_waitpid");
+    waitpid(pid, exit_code_ptr)
 }
 
 pub fn update_task_info(syscall_id: usize , _ti: *mut TaskInfo){
     let ti_ref: &mut TaskInfo = unsafe { &mut *_ti };
     ti_ref.syscall_times[syscall_id]+=1;
-}
\ No newline at end of file
+}
@@ -10,23 +10,44 @@
 //! might not be what you expect.
 
 mod context;
+mod pid;
+mod scheduler;
 mod switch;
 #[allow(clippy::module_inception)]
 mod task;
 
 use core::mem;
+use core::mem::size_of;
 
-use crate::config::MAX_APP_NUM;
-use crate::loader::{get_num_app, init_app_cx};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use scheduler::{new_scheduler, Scheduler};
+
+use crate::loader::{get_app_data, get_app_data_by_name, get_num_app};
+use crate::mm::{translated_byte_buffer, MapPermission, VirtAddr};
 use crate::sync::UPSafeCell;
 use crate::syscall::process::TaskInfo;
 use crate::timer::get_time_ms;
 use lazy_static::*;
 use switch::__switch;
+pub use pid::{pid_alloc, KernelStack, PidHandle};
 pub use task::{TaskControlBlock, TaskStatus};
 
 pub use context::TaskContext;
 
+/// Big stride used by the stride scheduler. A task's `pass` is
+/// `BIG_STRIDE / priority`, so a higher priority yields a smaller pass and the
+/// task is scheduled more often, giving roughly priority-proportional CPU time.
+pub(crate) const BIG_STRIDE: usize = 0xFFFF_FFFF;
+
+/// Compare two strides with wrapping arithmetic so a task whose stride has
+/// wrapped past `usize::MAX` is still treated as "behind". `a` is considered
+/// smaller than `b` when `b.wrapping_sub(a)` is below `BIG_STRIDE / 2`.
+pub(crate) fn stride_lt(a: usize, b: usize) -> bool {
+    b.wrapping_sub(a) < BIG_STRIDE / 2
+}
+
 /// The task manager, where all the tasks are managed.
 ///
 /// Functions implemented on `TaskManager` deals with all task state transitions
@@ -37,7 +58,7 @@ pub use context::TaskContext;
 /// borrowing checks to runtime. You can see examples on how to use `inner` in
 /// existing functions on `TaskManager`.
 pub struct TaskManager {
-    /// total number of tasks
+    /// total number of tasks loaded at boot
     num_app: usize,
     /// use inner value to get mutable access
     inner: UPSafeCell<TaskManagerInner>,
@@ -45,10 +66,15 @@ pub struct TaskManager {
 
 /// Inner of Task Manager
 pub struct TaskManagerInner {
-    /// task list
-    tasks: [TaskControlBlock; MAX_APP_NUM],
-    /// id of current `Running` task
+    /// every live process, keyed by pid. A `BTreeMap` rather than a flat
+    /// `Vec` because pids are recycled by [`pid::PidAllocator`] and may leave
+    /// holes, and because [`Scheduler`] already addresses tasks by the same
+    /// `usize` id.
+    tasks: BTreeMap<usize, Arc<TaskControlBlock>>,
+    /// pid of the `Running` task
     current_task: usize,
+    /// ready-queue policy (FIFO or stride, chosen at build time)
+    scheduler: Box<dyn Scheduler>,
 }
 
 lazy_static! {
@@ -56,25 +82,25 @@ lazy_static! {
     pub static ref TASK_MANAGER: TaskManager = {
         trace!("TASK MANAGER init");
         let num_app = get_num_app();
-        let mut tasks = [TaskControlBlock{
-            task_cx: TaskContext::zero_init(),
-            task_info: TaskInfo::new(),
-            start_time: 0,
-        }; MAX_APP_NUM];
-        trace!("TASK MANAGER change status");
-        for (i, task) in tasks.iter_mut().enumerate() {
-            task.task_cx = TaskContext::goto_restore(init_app_cx(i));
-            task.task_info.status = TaskStatus::Ready;
+        let mut tasks = BTreeMap::new();
+        let mut scheduler = new_scheduler();
+        for i in 0..num_app {
+            let task = Arc::new(TaskControlBlock::new(get_app_data(i)));
+            let pid = task.getpid();
+            tasks.insert(pid, task);
+            // pid 0 is run directly by `run_first_task`; the rest wait in the queue
+            if pid != 0 {
+                scheduler.insert(pid);
+            }
         }
-        //[kernel] boot_stack top=bottom=0x80270000, lower_bound=0x80260000
-        //[ INFO] Size of TASK_MANAGER: 34200 (decimal)
-        info!("Size of TASK_MANAGER: {}", mem::size_of::<TaskManager>());
+        info!("Size of TaskManager: {}", mem::size_of::<TaskManager>());
         TaskManager {
             num_app,
             inner: unsafe {
                 UPSafeCell::new(TaskManagerInner {
                     tasks,
                     current_task: 0,
+                    scheduler,
                 })
             },
         }
@@ -82,18 +108,36 @@ lazy_static! {
 }
 
 impl TaskManager {
+    /// The task currently running, i.e. the one whose pid is `current_task`.
+    fn current_task(&self) -> Arc<TaskControlBlock> {
+        let inner = self.inner.exclusive_access();
+        inner
+            .tasks
+            .get(&inner.current_task)
+            .cloned()
+            .expect("current task missing from task table")
+    }
     /// Run the first task in task list.
     ///
     /// Generally, the first task in task list is an idle task (we call it zero process later).
     /// But in ch3, we load apps statically, so the first task is a real app.
     fn run_first_task(&self) -> ! {
         trace!("Run first task id = 0");
-        let mut inner = self.inner.exclusive_access();
-        let task0 = &mut inner.tasks[0];
-        task0.task_info.status = TaskStatus::Running;
-        task0.start_time = get_time_ms();
-        let next_task_cx_ptr = &task0.task_cx as *const TaskContext;
-        drop(inner);
+        let task0 = {
+            let mut inner = self.inner.exclusive_access();
+            inner.current_task = 0;
+            inner
+                .tasks
+                .get(&0)
+                .cloned()
+                .expect("no pid-0 task to boot")
+        };
+        let next_task_cx_ptr = {
+            let mut task0_inner = task0.inner_exclusive_access();
+            task0_inner.task_status = TaskStatus::Running;
+            task0_inner.start_time = get_time_ms();
+            &task0_inner.task_cx as *const TaskContext
+        };
         let mut _unused = TaskContext::zero_init();
         // before this, we should drop local variables that must be dropped manually
         // context换出一个虚拟上下文
@@ -105,44 +149,89 @@ impl TaskManager {
         panic!("unreachable in run_first_task!");
     }
 
-    /// Change the status of current `Running` task into `Ready`.
+    /// Change the status of current `Running` task into `Ready` and put it
+    /// back into the ready queue so it can be scheduled again.
     fn mark_current_suspended(&self) {
         let mut inner = self.inner.exclusive_access();
         let current = inner.current_task;
-        inner.tasks[current].task_info.status = TaskStatus::Ready;
+        let task = inner
+            .tasks
+            .get(&current)
+            .cloned()
+            .expect("current task missing from task table");
+        task.inner_exclusive_access().task_status = TaskStatus::Ready;
+        inner.scheduler.insert(current);
     }
 
-    /// Change the status of current `Running` task into `Exited`.
-    fn mark_current_exited(&self) {
+    /// Change the status of current `Running` task into `Exited`, record its
+    /// exit code, and reparent its surviving children to the pid-0 task so
+    /// they can still be collected by a `waitpid`. An exited task is not
+    /// re-inserted into the ready queue; it stays in the task table as a
+    /// zombie until its parent reaps it.
+    fn mark_current_exited(&self, exit_code: i32) {
         let mut inner = self.inner.exclusive_access();
         let current = inner.current_task;
-        inner.tasks[current].task_info.status = TaskStatus::Exited;
-    }
+        let task = inner
+            .tasks
+            .get(&current)
+            .cloned()
+            .expect("current task missing from task table");
+        inner.scheduler.remove(current);
+        drop(inner);
 
-    /// Find next task to run and return task id.
-    ///
-    /// In this case, we only return the first `Ready` task in task list.
-    fn find_next_task(&self) -> Option<usize> {
-        let inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        (current + 1..current + self.num_app + 1)
-            .map(|id| id % self.num_app)
-            .find(|id| inner.tasks[*id].task_info.status == TaskStatus::Ready)
+        let children = {
+            let mut task_inner = task.inner_exclusive_access();
+            task_inner.task_status = TaskStatus::Exited;
+            task_inner.exit_code = exit_code;
+            core::mem::take(&mut task_inner.children)
+        };
+        if !children.is_empty() {
+            if let Some(init) = self.inner.exclusive_access().tasks.get(&0).cloned() {
+                let mut init_inner = init.inner_exclusive_access();
+                for child in children {
+                    child.inner_exclusive_access().parent = Some(Arc::downgrade(&init));
+                    init_inner.children.push(child);
+                }
+            }
+            // an exited pid 0 stays in the table as a zombie (it has no parent
+            // to reap it), so it still adopts them; only if pid 0 is missing
+            // from the table are they dropped once their last `Arc` goes away
+        }
     }
 
-    /// Switch current `Running` task to the task we have found,
+    /// Switch current `Running` task to the one the scheduler pops next,
     /// or there is no `Ready` task and we can exit with all applications completed
     fn run_next_task(&self) {
-        if let Some(next) = self.find_next_task() {
-            let mut inner = self.inner.exclusive_access();
-            let current = inner.current_task;
-            trace!("Run first task id = {}",next);
-            inner.tasks[next].task_info.status = TaskStatus::Running;
-            inner.tasks[next].task_info.time = get_time_ms();
-            inner.current_task = next;
-            let current_task_cx_ptr = &mut inner.tasks[current].task_cx as *mut TaskContext;
-            let next_task_cx_ptr = &inner.tasks[next].task_cx as *const TaskContext;
-            drop(inner);
+        let next = self.inner.exclusive_access().scheduler.pop();
+        if let Some(next) = next {
+            let (current_task, next_task) = {
+                let mut inner = self.inner.exclusive_access();
+                let current = inner.current_task;
+                let current_task = inner
+                    .tasks
+                    .get(&current)
+                    .cloned()
+                    .expect("current task missing from task table");
+                let next_task = inner
+                    .tasks
+                    .get(&next)
+                    .cloned()
+                    .expect("scheduler handed out a pid with no task");
+                inner.current_task = next;
+                (current_task, next_task)
+            };
+            trace!("Run first task id = {}", next);
+            let current_task_cx_ptr =
+                &mut current_task.inner_exclusive_access().task_cx as *mut TaskContext;
+            let next_task_cx_ptr = {
+                let mut next_inner = next_task.inner_exclusive_access();
+                next_inner.task_status = TaskStatus::Running;
+                if next_inner.start_time == 0 {
+                    next_inner.start_time = get_time_ms();
+                }
+                next_inner.task_info.time = get_time_ms();
+                &next_inner.task_cx as *const TaskContext
+            };
             // before this, we should drop local variables that must be dropped manually
             unsafe {
                 trace!("Switch task context");
@@ -153,19 +242,151 @@ impl TaskManager {
             panic!("All applications completed!");
         }
     }
-    /// get inner control block
-    pub fn get_current_task(&self) -> TaskControlBlock{
-        let inner = self.inner.exclusive_access();
-        inner.tasks[inner.current_task]
+    /// Set the priority of the current task, rejecting values below 2.
+    fn set_current_priority(&self, priority: usize) -> isize {
+        if priority < 2 {
+            return -1;
+        }
+        let current = self.inner.exclusive_access().current_task;
+        self.current_task().inner_exclusive_access().priority = priority;
+        self.inner
+            .exclusive_access()
+            .scheduler
+            .set_priority(current, priority);
+        priority as isize
     }
-    /// update task info according to current task
-    pub fn update_task_info(&self, syscall_id:usize, called_time:usize){
+    /// Copy of the current task's syscall/timing bookkeeping.
+    pub fn get_current_task_info(&self) -> TaskInfo {
+        self.current_task().inner_exclusive_access().task_info
+    }
+    /// Page-table token (satp) of the current task's address space.
+    pub fn get_current_token(&self) -> usize {
+        self.current_task().get_user_token()
+    }
+    /// Map an anonymous range into the current task's address space.
+    ///
+    /// `prot` carries the user-facing permission bits (bit0 read, bit1 write,
+    /// bit2 exec); the range is always user-accessible. Rejects unset or
+    /// out-of-range protection bits before handing off to [`MemorySet::mmap`].
+    fn mmap(&self, start: usize, len: usize, prot: usize) -> isize {
+        if prot & !0x7 != 0 || prot & 0x7 == 0 {
+            return -1;
+        }
+        let mut perm = MapPermission::U;
+        if prot & 0x1 != 0 {
+            perm |= MapPermission::R;
+        }
+        if prot & 0x2 != 0 {
+            perm |= MapPermission::W;
+        }
+        if prot & 0x4 != 0 {
+            perm |= MapPermission::X;
+        }
+        let task = self.current_task();
+        match task
+            .inner_exclusive_access()
+            .memory_set
+            .mmap(VirtAddr::from(start), len, perm)
+        {
+            Ok(()) => 0,
+            Err(_) => -1,
+        }
+    }
+    /// Unmap a range from the current task's address space.
+    fn munmap(&self, start: usize, len: usize) -> isize {
+        let task = self.current_task();
+        match task
+            .inner_exclusive_access()
+            .memory_set
+            .munmap(VirtAddr::from(start), len)
+        {
+            Ok(()) => 0,
+            Err(_) => -1,
+        }
+    }
+    /// Pid of the current task.
+    fn get_current_pid(&self) -> usize {
+        self.inner.exclusive_access().current_task
+    }
+    /// Duplicate the current process: clone its address space copy-on-write, allocate
+    /// the child a fresh pid and kernel stack, link it into the parent's
+    /// `children`, and hand it to the scheduler. The parent observes the
+    /// child's pid as the return value; the child observes 0.
+    fn fork(&self) -> isize {
+        let current = self.current_task();
+        let child = current.fork();
+        let child_pid = child.getpid();
+        // `fork` returns 0 in the child
+        child.get_trap_cx().x[10] = 0;
         let mut inner = self.inner.exclusive_access();
-        let current_idx = inner.current_task;
-        inner.tasks[current_idx].task_info.syscall_times[syscall_id]+=1;
-        inner.tasks[current_idx].task_info.time = called_time - inner.tasks[current_idx].start_time;
-        trace!("current syscall_times_id = {}",inner.tasks[current_idx].task_info.syscall_times[syscall_id]);
-        info!("update taskinfo on current task = {} , syscall_id = {}, syscall times = {}, time = {}",inner.current_task,syscall_id,inner.tasks[current_idx].task_info.syscall_times[syscall_id],inner.tasks[current_idx].task_info.time);
+        inner.tasks.insert(child_pid, child);
+        inner.scheduler.insert(child_pid);
+        child_pid as isize
+    }
+    /// Replace the current task's address space with the app named `path`,
+    /// keeping its pid and kernel stack.
+    fn exec(&self, path: &str) -> isize {
+        match get_app_data_by_name(path) {
+            Some(data) => {
+                self.current_task().exec(data);
+                0
+            }
+            None => -1,
+        }
+    }
+    /// Reap a zombie child of the current task, writing its exit code
+    /// through the page table to `exit_code_ptr`. `pid == -1` matches any
+    /// child. Returns the reaped child's pid, `-1` if there is no matching
+    /// child at all, or `-2` if a matching child exists but hasn't exited yet.
+    fn waitpid(&self, pid: isize, exit_code_ptr: *mut i32) -> isize {
+        let current = self.current_task();
+        let (any_match, idx) = {
+            let inner = current.inner_exclusive_access();
+            let matches = |c: &Arc<TaskControlBlock>| pid == -1 || pid as usize == c.getpid();
+            (
+                inner.children.iter().any(|c| matches(c)),
+                inner.children.iter().position(|c| {
+                    matches(c) && c.inner_exclusive_access().task_status == TaskStatus::Exited
+                }),
+            )
+        };
+        let idx = match idx {
+            Some(idx) => idx,
+            None if any_match => return -2,
+            None => return -1,
+        };
+        let child = current.inner_exclusive_access().children.remove(idx);
+        let child_pid = child.getpid();
+        let exit_code = child.inner_exclusive_access().exit_code;
+        self.inner.exclusive_access().tasks.remove(&child_pid);
+        let token = current.get_user_token();
+        let mut buffers = translated_byte_buffer(token, exit_code_ptr as *const u8, size_of::<i32>());
+        let src = exit_code.to_ne_bytes();
+        let mut offset = 0;
+        for buffer in &mut buffers {
+            let len = buffer.len();
+            buffer.copy_from_slice(&src[offset..offset + len]);
+            offset += len;
+        }
+        child_pid as isize
+    }
+    /// update task info according to current task
+    pub fn update_task_info(&self, syscall_id: usize, called_time: usize) {
+        let task = self.current_task();
+        let mut inner = task.inner_exclusive_access();
+        inner.task_info.syscall_times[syscall_id] += 1;
+        inner.task_info.time = called_time - inner.start_time;
+        trace!(
+            "current syscall_times_id = {}",
+            inner.task_info.syscall_times[syscall_id]
+        );
+        info!(
+            "update taskinfo on current task = {} , syscall_id = {}, syscall times = {}, time = {}",
+            self.inner.exclusive_access().current_task,
+            syscall_id,
+            inner.task_info.syscall_times[syscall_id],
+            inner.task_info.time
+        );
     }
 }
 
@@ -185,9 +406,51 @@ fn mark_current_suspended() {
     TASK_MANAGER.mark_current_suspended();
 }
 
-/// Change the status of current `Running` task into `Exited`.
-fn mark_current_exited() {
-    TASK_MANAGER.mark_current_exited();
+/// Change the status of current `Running` task into `Exited`, recording its
+/// exit code for a future `waitpid`.
+fn mark_current_exited(exit_code: i32) {
+    TASK_MANAGER.mark_current_exited(exit_code);
+}
+
+/// Set the priority of the current 'Running' task.
+pub fn set_current_priority(priority: usize) -> isize {
+    TASK_MANAGER.set_current_priority(priority)
+}
+
+/// Page-table token (satp) of the current task's address space.
+pub fn current_user_token() -> usize {
+    TASK_MANAGER.get_current_token()
+}
+
+/// Map an anonymous range into the current task's address space.
+pub fn mmap(start: usize, len: usize, prot: usize) -> isize {
+    TASK_MANAGER.mmap(start, len, prot)
+}
+
+/// Unmap a range from the current task's address space.
+pub fn munmap(start: usize, len: usize) -> isize {
+    TASK_MANAGER.munmap(start, len)
+}
+
+/// Pid of the current task.
+pub fn getpid() -> isize {
+    TASK_MANAGER.get_current_pid() as isize
+}
+
+/// Duplicate the current process into a child with its own, independent copy
+/// of the address space (see [`crate::mm::MemorySet::from_existed_user`]).
+pub fn fork() -> isize {
+    TASK_MANAGER.fork()
+}
+
+/// Replace the current process image with the app named `path`.
+pub fn exec(path: &str) -> isize {
+    TASK_MANAGER.exec(path)
+}
+
+/// Reap a zombie child and collect its exit code.
+pub fn waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+    TASK_MANAGER.waitpid(pid, exit_code_ptr)
 }
 
 /// Suspend the current 'Running' task and run the next task in task list.
@@ -196,8 +459,9 @@ pub fn suspend_current_and_run_next() {
     run_next_task();
 }
 
-/// Exit the current 'Running' task and run the next task in task list.
-pub fn exit_current_and_run_next() {
-    mark_current_exited();
+/// Exit the current 'Running' task with `exit_code` and run the next task in
+/// task list.
+pub fn exit_current_and_run_next(exit_code: i32) {
+    mark_current_exited(exit_code);
     run_next_task();
 }
@@ -0,0 +1,133 @@
+//! Scheduling policies for the [`TaskManager`].
+//!
+//! The ready-queue is hidden behind the [`Scheduler`] trait so that the kernel
+//! can swap policies at build time without touching `TaskManager` itself. Two
+//! implementations are shipped: a plain FIFO ring ([`FifoScheduler`]) and a
+//! proportional-share [`StrideScheduler`]. The active one is chosen by the
+//! `stride` cargo feature through [`new_scheduler`].
+
+use super::{stride_lt, BIG_STRIDE};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+
+/// Default priority handed to a task before `sys_set_priority` is called.
+const DEFAULT_PRIORITY: usize = 16;
+
+/// A scheduling policy over the ids of `Ready` tasks.
+///
+/// The policy only tracks which tasks are runnable and in what order; the task
+/// table itself still lives in `TaskManagerInner`.
+pub trait Scheduler {
+    /// Mark the task `id` as ready to run.
+    fn insert(&mut self, id: usize);
+    /// Return the id that would run next without removing it.
+    fn peek(&self) -> Option<usize>;
+    /// Remove and return the id that should run next.
+    fn pop(&mut self) -> Option<usize>;
+    /// Drop `id` from the ready set, e.g. when it exits.
+    fn remove(&mut self, id: usize);
+    /// Update the scheduling priority of `id`. No-op for policies that ignore
+    /// priority (such as FIFO).
+    fn set_priority(&mut self, _id: usize, _priority: usize) {}
+}
+
+/// First-in-first-out scheduler backed by a ring of ready task ids.
+pub struct FifoScheduler {
+    ready: VecDeque<usize>,
+}
+
+impl FifoScheduler {
+    /// Create an empty FIFO scheduler.
+    pub fn new() -> Self {
+        Self {
+            ready: VecDeque::new(),
+        }
+    }
+}
+
+impl Scheduler for FifoScheduler {
+    fn insert(&mut self, id: usize) {
+        self.ready.push_back(id);
+    }
+    fn peek(&self) -> Option<usize> {
+        self.ready.front().copied()
+    }
+    fn pop(&mut self) -> Option<usize> {
+        self.ready.pop_front()
+    }
+    fn remove(&mut self, id: usize) {
+        self.ready.retain(|&x| x != id);
+    }
+}
+
+/// Stride scheduler: always runs the ready task with the smallest `stride`,
+/// advancing it by `BIG_STRIDE / priority` so higher-priority tasks (smaller
+/// pass) are picked more often. Strides are compared with [`stride_lt`] so the
+/// ordering stays correct across `usize` overflow.
+pub struct StrideScheduler {
+    /// ready task ids
+    ready: VecDeque<usize>,
+    /// per-task stride, advanced on every `pop`
+    stride: BTreeMap<usize, usize>,
+    /// per-task priority (at least 2)
+    priority: BTreeMap<usize, usize>,
+}
+
+impl StrideScheduler {
+    /// Create an empty stride scheduler.
+    pub fn new() -> Self {
+        Self {
+            ready: VecDeque::new(),
+            stride: BTreeMap::new(),
+            priority: BTreeMap::new(),
+        }
+    }
+}
+
+impl Scheduler for StrideScheduler {
+    fn insert(&mut self, id: usize) {
+        self.stride.entry(id).or_insert(0);
+        self.priority.entry(id).or_insert(DEFAULT_PRIORITY);
+        if !self.ready.contains(&id) {
+            self.ready.push_back(id);
+        }
+    }
+    fn peek(&self) -> Option<usize> {
+        self.ready
+            .iter()
+            .copied()
+            .reduce(|best, id| {
+                if stride_lt(self.stride[&id], self.stride[&best]) {
+                    id
+                } else {
+                    best
+                }
+            })
+    }
+    fn pop(&mut self) -> Option<usize> {
+        let next = self.peek()?;
+        self.ready.retain(|&x| x != next);
+        let pass = BIG_STRIDE / self.priority[&next];
+        let stride = self.stride.get_mut(&next).unwrap();
+        *stride = stride.wrapping_add(pass);
+        Some(next)
+    }
+    fn remove(&mut self, id: usize) {
+        self.ready.retain(|&x| x != id);
+    }
+    fn set_priority(&mut self, id: usize, priority: usize) {
+        self.priority.insert(id, priority);
+    }
+}
+
+/// Build the scheduler selected by the active cargo feature.
+#[cfg(not(feature = "stride"))]
+pub fn new_scheduler() -> Box<dyn Scheduler> {
+    Box::new(FifoScheduler::new())
+}
+
+/// Build the scheduler selected by the active cargo feature.
+#[cfg(feature = "stride")]
+pub fn new_scheduler() -> Box<dyn Scheduler> {
+    Box::new(StrideScheduler::new())
+}
@@ -0,0 +1,203 @@
+//! Task control block: the process abstraction.
+//!
+//! A process is a [`TaskControlBlock`], shared via `Arc` between the global
+//! task table, its parent's `children` list and, while it is runnable,
+//! whichever scheduler slot names its pid. Its mutable state lives behind
+//! `inner`, the same "immutable handle + `UPSafeCell` interior" split used by
+//! every other shared structure in this kernel (`PageTable`'s frames aside,
+//! see e.g. `KERNEL_SPACE`).
+
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::cell::RefMut;
+
+use super::pid::{pid_alloc, KernelStack, PidHandle};
+use super::TaskContext;
+use crate::config::TRAP_CONTEXT_BASE;
+use crate::mm::{MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::sync::UPSafeCell;
+use crate::syscall::process::TaskInfo;
+use crate::trap::{trap_handler, TrapContext};
+
+/// Life-cycle state of a [`TaskControlBlock`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TaskStatus {
+    /// allocated but never scheduled
+    UnInit,
+    /// runnable, waiting in the scheduler
+    Ready,
+    /// currently on the CPU
+    Running,
+    /// terminated; a zombie until its parent collects its exit code with `waitpid`
+    Exited,
+}
+
+/// A process. The pid and kernel stack are owned for the task's whole
+/// lifetime and released on drop; everything that changes while the task
+/// runs lives in `inner`.
+pub struct TaskControlBlock {
+    /// process identifier, freed back to the global pid allocator on drop
+    pub pid: PidHandle,
+    /// kernel stack mapped into `KERNEL_SPACE`, unmapped on drop
+    pub kernel_stack: KernelStack,
+    inner: UPSafeCell<TaskControlBlockInner>,
+}
+
+/// Mutable state of a [`TaskControlBlock`].
+pub struct TaskControlBlockInner {
+    /// physical page holding this task's `TrapContext`
+    pub trap_cx_ppn: PhysPageNum,
+    /// top of the highest mapped user address, the base for `sbrk`
+    pub base_size: usize,
+    /// saved kernel-side register context for `__switch`
+    pub task_cx: TaskContext,
+    /// life-cycle state
+    pub task_status: TaskStatus,
+    /// user address space
+    pub memory_set: MemorySet,
+    /// parent process, if any (weak to avoid a parent/child reference cycle)
+    pub parent: Option<Weak<TaskControlBlock>>,
+    /// live children; reparented to the pid-0 task when this task exits
+    pub children: Vec<Arc<TaskControlBlock>>,
+    /// exit code reported to `waitpid` once this task becomes a zombie
+    pub exit_code: i32,
+    /// per-syscall bookkeeping surfaced by `sys_task_info`
+    pub task_info: TaskInfo,
+    /// wall-clock ms when this task was first scheduled
+    pub start_time: usize,
+    /// stride-scheduling priority (at least 2)
+    pub priority: usize,
+    /// initial stride (0); never advanced here, as the stride scheduler keeps
+    /// each task's running stride itself
+    pub stride: usize,
+}
+
+impl TaskControlBlockInner {
+    fn get_user_token(&self) -> usize {
+        self.memory_set.token()
+    }
+}
+
+impl TaskControlBlock {
+    /// Borrow the mutable state, deferring the borrow check to runtime like
+    /// every other `UPSafeCell` in this kernel.
+    pub fn inner_exclusive_access(&self) -> RefMut<'_, TaskControlBlockInner> {
+        self.inner.exclusive_access()
+    }
+    /// Page-table token (`satp`) of this task's address space.
+    pub fn get_user_token(&self) -> usize {
+        self.inner_exclusive_access().get_user_token()
+    }
+    /// This task's pid.
+    pub fn getpid(&self) -> usize {
+        self.pid.0
+    }
+    /// This task's `TrapContext`, addressed directly through its physical
+    /// page (valid as long as the page stays resident).
+    pub fn get_trap_cx(&self) -> &'static mut TrapContext {
+        self.inner_exclusive_access().trap_cx_ppn.get_mut()
+    }
+    /// Build a fresh, parentless process around an ELF image.
+    pub fn new(elf_data: &[u8]) -> Self {
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT_BASE).into())
+            .unwrap()
+            .ppn();
+        let pid_handle = pid_alloc();
+        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack_top = kernel_stack.get_top();
+        let task_control_block = Self {
+            pid: pid_handle,
+            kernel_stack,
+            inner: unsafe {
+                UPSafeCell::new(TaskControlBlockInner {
+                    trap_cx_ppn,
+                    base_size: user_sp,
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status: TaskStatus::Ready,
+                    memory_set,
+                    parent: None,
+                    children: Vec::new(),
+                    exit_code: 0,
+                    task_info: TaskInfo::default(),
+                    start_time: 0,
+                    priority: 16,
+                    stride: 0,
+                })
+            },
+        };
+        *task_control_block.get_trap_cx() = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            kernel_stack_top,
+            trap_handler as usize,
+        );
+        task_control_block
+    }
+    /// Duplicate this process for `fork`, sharing its address space
+    /// copy-on-write via [`MemorySet::from_existed_user`]. The child starts
+    /// `Ready`, with its own pid and kernel stack, and this task as its
+    /// parent. The caller still has to zero the child's `a0` so it observes
+    /// `fork`'s "0 in the child" return-value convention.
+    pub fn fork(self: &Arc<Self>) -> Arc<Self> {
+        let mut parent_inner = self.inner_exclusive_access();
+        let memory_set = MemorySet::from_existed_user(&parent_inner.memory_set);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT_BASE).into())
+            .unwrap()
+            .ppn();
+        let pid_handle = pid_alloc();
+        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack_top = kernel_stack.get_top();
+        let child = Arc::new(Self {
+            pid: pid_handle,
+            kernel_stack,
+            inner: unsafe {
+                UPSafeCell::new(TaskControlBlockInner {
+                    trap_cx_ppn,
+                    base_size: parent_inner.base_size,
+                    task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                    task_status: TaskStatus::Ready,
+                    memory_set,
+                    parent: Some(Arc::downgrade(self)),
+                    children: Vec::new(),
+                    exit_code: 0,
+                    task_info: TaskInfo::default(),
+                    start_time: 0,
+                    priority: parent_inner.priority,
+                    stride: 0,
+                })
+            },
+        });
+        parent_inner.children.push(child.clone());
+        // the copied TrapContext still has the parent's kernel_sp; point it
+        // at the child's own kernel stack
+        child.get_trap_cx().kernel_sp = kernel_stack_top;
+        child
+    }
+    /// Replace this process's address space with `elf_data`, keeping its
+    /// pid, kernel stack and parent/children links. Used by `exec`.
+    pub fn exec(&self, elf_data: &[u8]) {
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT_BASE).into())
+            .unwrap()
+            .ppn();
+        let kernel_stack_top = self.kernel_stack.get_top();
+        {
+            let mut inner = self.inner_exclusive_access();
+            inner.memory_set = memory_set;
+            inner.trap_cx_ppn = trap_cx_ppn;
+            inner.base_size = user_sp;
+        }
+        *self.get_trap_cx() = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            kernel_stack_top,
+            trap_handler as usize,
+        );
+    }
+}